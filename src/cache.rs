@@ -0,0 +1,61 @@
+use std::num::NonZeroUsize;
+use std::sync::RwLock;
+
+use lru::LruCache;
+
+/// the finished output of a meme render, cheap enough to clone out of the cache and hand to a
+/// response.
+#[derive(Clone)]
+pub struct ResponseCacheEntry {
+	pub encoding_bytes: Vec<u8>,
+	pub content_type: String,
+}
+
+/// an in-memory, content-addressed cache of recently rendered memes.
+///
+/// keyed by a hash of the request parameters that fully determine the output bytes, so the
+/// same `{type}` + query string never gets re-rendered while it's still warm in the cache.
+pub struct ResponseCache {
+	cache: RwLock<LruCache<u64, ResponseCacheEntry>>,
+}
+
+impl ResponseCache {
+	pub fn new(capacity: usize) -> Self {
+		ResponseCache {
+			cache: RwLock::new(LruCache::new(
+				NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::new(1).unwrap()),
+			)),
+		}
+	}
+
+	pub fn get(&self, key: u64) -> Option<ResponseCacheEntry> {
+		self.cache.write().unwrap().get(&key).cloned()
+	}
+
+	pub fn put(&self, key: u64, entry: ResponseCacheEntry) {
+		self.cache.write().unwrap().put(key, entry);
+	}
+}
+
+/// hashes the parameters that fully determine a rendered meme's bytes, using a fast
+/// non-cryptographic hasher since this key never leaves the process.
+#[allow(clippy::too_many_arguments)]
+pub fn cache_key(
+	image_type: &str,
+	url: &str,
+	size: u32,
+	method: &str,
+	flip: bool,
+	format: &str,
+	quality: u8,
+	background: &str,
+	tint: &str,
+	tint_strength: f32,
+) -> u64 {
+	let canonical = format!(
+		"{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+		image_type, url, size, method, flip, format, quality, background, tint, tint_strength
+	);
+
+	seahash::hash(canonical.as_bytes())
+}
@@ -1,9 +1,19 @@
 use dotenv::vars;
 
+/// default cap on a single remote avatar download, in bytes (10 MiB).
+const DEFAULT_MAX_FILE_SIZE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// default number of rendered memes kept in the in-memory result cache.
+const DEFAULT_CACHE_CAPACITY: usize = 256;
+
 #[derive(Debug)]
 pub struct Config {
 	pub in_production: bool,
 	pub server_addr: String,
+	/// maximum number of bytes allowed when downloading a remote avatar. `0` means unlimited.
+	pub max_file_size_bytes: u64,
+	/// how many rendered memes the in-memory result cache holds at once.
+	pub cache_capacity: usize,
 }
 impl Config {
 	pub fn new() -> Self {
@@ -16,12 +26,27 @@ impl Config {
 		};
 		let port = find_key(&environment_vars, "IMAGE_API_PORT");
 
+		let max_file_size_bytes = match find_key_optional(&environment_vars, "MAX_FILE_SIZE_BYTES")
+		{
+			Some(value) => value
+				.parse()
+				.expect("MAX_FILE_SIZE_BYTES isn't a valid number"),
+			None => DEFAULT_MAX_FILE_SIZE_BYTES,
+		};
+
+		let cache_capacity = match find_key_optional(&environment_vars, "CACHE_CAPACITY") {
+			Some(value) => value.parse().expect("CACHE_CAPACITY isn't a valid number"),
+			None => DEFAULT_CACHE_CAPACITY,
+		};
+
 		Config {
 			in_production,
 			server_addr: match in_production {
 				true => format!("0.0.0.0:{}", &port),
 				false => format!("127.0.0.1:{}", &port),
 			},
+			max_file_size_bytes,
+			cache_capacity,
 		}
 	}
 }
@@ -35,3 +60,13 @@ pub fn find_key(iteration: &[(String, String)], key_search: &'static str) -> Str
 		),
 	}
 }
+
+pub fn find_key_optional(
+	iteration: &[(String, String)],
+	key_search: &'static str,
+) -> Option<String> {
+	iteration
+		.iter()
+		.find(|(key, _)| key == key_search)
+		.map(|(_, value)| value.to_string())
+}
@@ -18,13 +18,28 @@ use derive_more::{Display, Error};
 use reqwest::header::{HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 
-use crate::image_manipulation::{ImageManipulationFunctionOptions, IMAGE_MANIPULATION};
+use crate::cache::{cache_key, ResponseCache, ResponseCacheEntry};
+use crate::config::Config;
+use crate::image_manipulation::{
+	ComposeRequest, ImageManipulationFunctionOptions, IMAGE_MANIPULATION,
+};
+use crate::image_utilities::{
+	parse_color, validate_jpeg_quality, validate_thumbnail_size, OutputFormat, ThumbnailMethod,
+	DEFAULT_JPEG_QUALITY, DEFAULT_THUMBNAIL_SIZE, DEFAULT_TINT_STRENGTH,
+};
+use image::Rgba;
 
 #[derive(Deserialize, Debug)]
 pub struct ImageQuery {
 	url: String,
-	large: Option<bool>,
+	size: Option<u32>,
+	method: Option<ThumbnailMethod>,
 	flip: Option<bool>,
+	format: Option<OutputFormat>,
+	quality: Option<u8>,
+	background: Option<String>,
+	tint: Option<String>,
+	tint_strength: Option<f32>,
 }
 
 #[derive(Serialize)]
@@ -50,19 +65,63 @@ impl error::ResponseError for APIResponseError {}
 pub async fn create(
 	image_type: web::Path<String>,
 	query: web::Query<ImageQuery>,
+	config: web::Data<Config>,
+	cache: web::Data<ResponseCache>,
 ) -> Result<HttpResponse, APIResponseError> {
 	let start_time = std::time::Instant::now();
 
+	let background = match &query.background {
+		Some(value) => parse_color(value)?,
+		None => Rgba([255, 255, 255, 255]),
+	};
+	let tint = query.tint.as_deref().map(parse_color).transpose()?;
+	let tint_strength = query.tint_strength.unwrap_or(DEFAULT_TINT_STRENGTH);
+	let size = validate_thumbnail_size(query.size.unwrap_or(DEFAULT_THUMBNAIL_SIZE))?;
+	let method = query.method.unwrap_or_default();
+	let flip = query.flip.unwrap_or(false);
+	let format = query.format.unwrap_or_default();
+	let quality = validate_jpeg_quality(query.quality.unwrap_or(DEFAULT_JPEG_QUALITY))?;
+	// `.gif` is deliberately left alone so an animated avatar's bytes survive the download;
+	// `load_avatar_from_url` detects and renders it frame-by-frame.
+	let cleaned_url = query
+		.url
+		.replace(".jpg", ".png")
+		.replace(".jpeg", ".png")
+		.replace(".webp", ".png");
+
+	let key = cache_key(
+		image_type.as_str(),
+		&cleaned_url,
+		size,
+		format!("{:?}", method).as_str(),
+		flip,
+		format!("{:?}", format).as_str(),
+		quality,
+		query.background.as_deref().unwrap_or(""),
+		query.tint.as_deref().unwrap_or(""),
+		tint_strength,
+	);
+
+	if let Some(entry) = cache.get(key) {
+		let mut response = HttpResponse::Ok()
+			.content_type(entry.content_type)
+			.body(entry.encoding_bytes);
+		insert_response_headers(&mut response, start_time.elapsed(), "HIT");
+		return Ok(response);
+	}
+
 	let result = IMAGE_MANIPULATION[image_type.as_str()](
-		&query
-			.url
-			.replace(".gif", ".png")
-			.replace(".jpg", ".png")
-			.replace(".jpeg", ".png")
-			.replace(".webp", ".png"),
+		&cleaned_url,
 		ImageManipulationFunctionOptions {
-			large: query.large.unwrap_or(false),
-			flip: query.flip.unwrap_or(false),
+			size,
+			method,
+			flip,
+			max_file_size_bytes: config.max_file_size_bytes,
+			format,
+			quality,
+			background,
+			tint,
+			tint_strength,
 		},
 	)
 	.await;
@@ -70,15 +129,67 @@ pub async fn create(
 	let end_time = start_time.elapsed();
 
 	match result {
-		Ok(mut value) => {
-			value.headers_mut().insert(
-				HeaderName::from_static("time-taken"),
-				HeaderValue::from_str(format!("{}", end_time.as_millis()).as_str()).unwrap(),
+		Ok(value) => {
+			let content_type = value
+				.headers()
+				.get(actix_web::http::header::CONTENT_TYPE)
+				.and_then(|value| value.to_str().ok())
+				.unwrap_or("application/octet-stream")
+				.to_owned();
+
+			let encoding_bytes = actix_web::body::to_bytes(value.into_body())
+				.await
+				.map_err(|error| APIResponseError::new(error.to_string()))?
+				.to_vec();
+
+			cache.put(
+				key,
+				ResponseCacheEntry {
+					encoding_bytes: encoding_bytes.clone(),
+					content_type: content_type.clone(),
+				},
 			);
-			Ok(value)
+
+			let mut response = HttpResponse::Ok()
+				.content_type(content_type)
+				.body(encoding_bytes);
+			insert_response_headers(&mut response, end_time, "MISS");
+			Ok(response)
 		}
 		Err(error) => Err(APIResponseError {
 			name: error.to_string(),
 		}),
 	}
 }
+
+/// accepts a JSON body describing an ordered list of layers and composites them onto a blank
+/// canvas, returning the encoded image. unlike `/images/{type}`, a new meme here is just a new
+/// JSON payload rather than a new compiled function.
+#[post("/compose")]
+pub async fn compose(
+	body: web::Json<ComposeRequest>,
+	config: web::Data<Config>,
+) -> Result<HttpResponse, APIResponseError> {
+	let start_time = std::time::Instant::now();
+
+	let mut response =
+		crate::image_manipulation::compose(body.into_inner(), config.max_file_size_bytes).await?;
+	insert_response_headers(&mut response, start_time.elapsed(), "MISS");
+
+	Ok(response)
+}
+
+fn insert_response_headers(
+	response: &mut HttpResponse,
+	elapsed: std::time::Duration,
+	cache_status: &str,
+) {
+	response.headers_mut().insert(
+		HeaderName::from_static("time-taken"),
+		HeaderValue::from_str(format!("{}", elapsed.as_millis()).as_str()).unwrap(),
+	);
+	response.headers_mut().insert(
+		HeaderName::from_static("x-cache"),
+		HeaderValue::from_str(cache_status).unwrap(),
+	);
+}
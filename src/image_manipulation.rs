@@ -13,25 +13,37 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
-use actix_web::http::header::ContentType;
 use actix_web::{HttpResponse, Result};
 use image::{
-	codecs::gif, imageops::FilterType, io::Reader as ImageReader, Delay, DynamicImage, Frame,
+	codecs::gif, imageops::FilterType, io::Reader as ImageReader, Delay, DynamicImage, Frame, Rgba,
 };
 use image::{GenericImage, RgbaImage};
+use serde::Deserialize;
 use std::{future, ops::Index, pin::Pin};
 
 use crate::handler::{APIResponseError, MessageResponse};
 use crate::image_utilities::{
-	load_avatar_from_url, out_of_bounds_crop, resolve_asset_path, round_image,
-	smallify_large_number, AlphaImplementations, CustomRotation, GifAssistant, PngAssistant,
-	ProperResultConversion,
+	apply_tint, image_request, load_avatar_from_url, out_of_bounds_crop, parse_color,
+	resolve_asset_path, round_image, scale_to_size, thumbnail, validate_jpeg_quality,
+	AlphaImplementations, Avatar, CustomRotation, EncodeAssistant, GifAssistant, OutputFormat,
+	ProperResultConversion, ThumbnailMethod, DEFAULT_JPEG_QUALITY,
 };
 
+/// the alpha threshold at or below which an avatar pixel is treated as transparent: skipped when
+/// overlaying onto the meme background, and left untouched by tinting.
+const ALPHA_THRESHOLD: u8 = 128;
+
 #[derive(Debug)]
 pub struct ImageManipulationFunctionOptions {
-	pub large: bool,
+	pub size: u32,
+	pub method: ThumbnailMethod,
 	pub flip: bool,
+	pub max_file_size_bytes: u64,
+	pub format: OutputFormat,
+	pub quality: u8,
+	pub background: Rgba<u8>,
+	pub tint: Option<Rgba<u8>>,
+	pub tint_strength: f32,
 }
 
 type ImageManipulationFunctionReturn =
@@ -81,65 +93,276 @@ pub fn enter(
 	options: ImageManipulationFunctionOptions,
 ) -> ImageManipulationFunctionReturn {
 	let static_image = image.to_owned();
-	Box::pin(async move {
-		let avatar = load_avatar_from_url(static_image, options.flip).await?;
-		let meme_image = resolve_asset_path("enter.png", options.large).await?;
+	Box::pin(async move { render_avatar_overlay(static_image, options, "1000/enter.png").await })
+}
+
+pub fn exit(
+	image: &str,
+	options: ImageManipulationFunctionOptions,
+) -> ImageManipulationFunctionReturn {
+	let static_image = image.to_owned();
+	Box::pin(async move { render_avatar_overlay(static_image, options, "1000/exit.png").await })
+}
 
-		let avatar_x = smallify_large_number(35, options.large);
-		let avatar_y = smallify_large_number(397, options.large);
-		let avatar_dimensions = smallify_large_number(603, options.large);
+/// overlays a user's avatar onto the meme asset at `asset_path`, at the fixed coordinates the
+/// `enter`/`exit` memes share. animated avatars render every frame onto a copy of the (static)
+/// meme background and are returned as a GIF; anything else falls back to the requested
+/// [`OutputFormat`].
+async fn render_avatar_overlay(
+	image: String,
+	options: ImageManipulationFunctionOptions,
+	asset_path: &str,
+) -> Result<HttpResponse, APIResponseError> {
+	let avatar = load_avatar_from_url(image, options.flip, options.max_file_size_bytes).await?;
+	let meme_image = resolve_asset_path(asset_path).await?;
+	let meme_image = thumbnail(meme_image, options.size, options.size, options.method);
 
-		let mut avatar = avatar
-			.resize_exact(avatar_dimensions, avatar_dimensions, FilterType::Triangle)
-			.to_rgba8();
+	let avatar_x = scale_to_size(35, options.size);
+	let avatar_y = scale_to_size(397, options.size);
+	let avatar_dimensions = scale_to_size(603, options.size);
 
-		round_image(&mut avatar);
+	match avatar {
+		Avatar::Static(avatar) => {
+			let mut avatar = avatar
+				.resize_exact(avatar_dimensions, avatar_dimensions, FilterType::Triangle)
+				.to_rgba8();
 
-		let mut meme_image = meme_image.to_rgba8();
-		meme_image
-			.copy_within_alpha_threshold(&avatar, avatar_x, avatar_y, 128)
-			.proper_result()?;
+			round_image(&mut avatar);
 
-		let meme_image = DynamicImage::ImageRgba8(meme_image);
+			if let Some(tint) = options.tint {
+				apply_tint(&mut avatar, tint, options.tint_strength, ALPHA_THRESHOLD);
+			}
 
-		let png_assistant = PngAssistant::create_png(meme_image)?;
+			let mut meme_image = meme_image.to_rgba8();
+			meme_image
+				.copy_within_alpha_threshold(&avatar, avatar_x, avatar_y, ALPHA_THRESHOLD)
+				.proper_result()?;
 
-		Ok(HttpResponse::Ok()
-			.content_type(ContentType::png())
-			.body(png_assistant.encoding_bytes))
-	})
+			let meme_image = DynamicImage::ImageRgba8(meme_image);
+
+			let encode_assistant = EncodeAssistant::encode(
+				meme_image,
+				options.format,
+				options.quality,
+				options.background,
+			)?;
+
+			Ok(HttpResponse::Ok()
+				.content_type(encode_assistant.content_type)
+				.body(encode_assistant.encoding_bytes))
+		}
+		Avatar::Animated(frames) => {
+			let background = meme_image.to_rgba8();
+			let gif_assistant = encode_animated_overlay(
+				&background,
+				frames,
+				avatar_x,
+				avatar_y,
+				avatar_dimensions,
+				options.tint,
+				options.tint_strength,
+			)?;
+
+			Ok(HttpResponse::Ok()
+				.content_type("image/gif")
+				.body(gif_assistant.encoding_bytes))
+		}
+	}
 }
 
-pub fn exit(
-	image: &str,
-	options: ImageManipulationFunctionOptions,
-) -> ImageManipulationFunctionReturn {
-	let static_image = image.to_owned();
-	Box::pin(async move {
-		let avatar = load_avatar_from_url(static_image, options.flip).await?;
-		let meme_image = resolve_asset_path("exit.png", options.large).await?;
+/// composites each avatar frame onto its own copy of `background`, preserving the frame's
+/// original [`Delay`], and encodes the sequence as an infinitely-looping GIF.
+#[allow(clippy::too_many_arguments)]
+fn encode_animated_overlay(
+	background: &RgbaImage,
+	frames: Vec<(RgbaImage, Delay)>,
+	avatar_x: u32,
+	avatar_y: u32,
+	avatar_dimensions: u32,
+	tint: Option<Rgba<u8>>,
+	tint_strength: f32,
+) -> Result<GifAssistant, APIResponseError> {
+	let mut encoding_bytes = Vec::new();
+	{
+		let mut encoder = gif::GifEncoder::new(&mut encoding_bytes);
+		encoder.set_repeat(gif::Repeat::Infinite).proper_result()?;
 
-		let avatar_x = smallify_large_number(35, options.large);
-		let avatar_y = smallify_large_number(397, options.large);
-		let avatar_dimensions = smallify_large_number(603, options.large);
+		let mut encoded_frames = Vec::with_capacity(frames.len());
+		for (avatar_frame, delay) in frames {
+			let mut avatar_frame = image::imageops::resize(
+				&avatar_frame,
+				avatar_dimensions,
+				avatar_dimensions,
+				FilterType::Triangle,
+			);
 
-		let mut avatar = avatar
-			.resize_exact(avatar_dimensions, avatar_dimensions, FilterType::Triangle)
-			.to_rgba8();
+			round_image(&mut avatar_frame);
 
-		round_image(&mut avatar);
+			if let Some(tint) = tint {
+				apply_tint(&mut avatar_frame, tint, tint_strength, ALPHA_THRESHOLD);
+			}
 
-		let mut meme_image = meme_image.to_rgba8();
-		meme_image
-			.copy_within_alpha_threshold(&avatar, avatar_x, avatar_y, 128)
-			.proper_result()?;
+			let mut composed = background.clone();
+			composed
+				.copy_within_alpha_threshold(&avatar_frame, avatar_x, avatar_y, ALPHA_THRESHOLD)
+				.proper_result()?;
 
-		let meme_image = DynamicImage::ImageRgba8(meme_image);
+			encoded_frames.push(Frame::from_parts(composed, 0, 0, delay));
+		}
 
-		let png_assistant = PngAssistant::create_png(meme_image)?;
+		encoder.encode_frames(encoded_frames).proper_result()?;
+	}
 
-		Ok(HttpResponse::Ok()
-			.content_type(ContentType::png())
-			.body(png_assistant.encoding_bytes))
-	})
+	Ok(GifAssistant { encoding_bytes })
+}
+
+/// the largest canvas or layer dimension a `/compose` request may specify. layer/canvas sizes
+/// are attacker-controlled JSON fields fed straight into `RgbaImage::from_pixel`/`resize_exact`
+/// before any network fetch happens, so an unbounded value is an unauthenticated way to make
+/// the process attempt a multi-exabyte allocation.
+const MAX_COMPOSE_DIMENSION: u32 = 2048;
+
+/// the most layers a single `/compose` request may specify, so a request can't force unbounded
+/// work by listing an enormous layer list.
+const MAX_COMPOSE_LAYERS: usize = 32;
+
+/// rejects a canvas or layer `width`/`height` of zero or above [`MAX_COMPOSE_DIMENSION`].
+fn validate_compose_dimensions(width: u32, height: u32) -> Result<(), APIResponseError> {
+	if width == 0 || height == 0 || width > MAX_COMPOSE_DIMENSION || height > MAX_COMPOSE_DIMENSION {
+		return Err(APIResponseError::new(format!(
+			"width and height must be between 1 and {}",
+			MAX_COMPOSE_DIMENSION
+		)));
+	}
+
+	Ok(())
+}
+
+/// a single layer of a `/compose` request, placed onto the canvas in list order.
+#[derive(Deserialize, Debug)]
+pub struct ComposeLayer {
+	/// a path under `assets/images/`, e.g. `"1000/enter.png"`. mutually exclusive with `url`.
+	pub asset: Option<String>,
+	/// a remote image to download. mutually exclusive with `asset`.
+	pub url: Option<String>,
+	pub x: i32,
+	pub y: i32,
+	pub width: u32,
+	pub height: u32,
+	/// crops the layer to a circle after resizing, same as the avatar overlay in `enter`/`exit`.
+	pub round: Option<bool>,
+	pub flip: Option<bool>,
+	/// degrees to rotate counter-clockwise via [`CustomRotation::rotate`].
+	pub rotate: Option<i32>,
+	/// defaults to [`ALPHA_THRESHOLD`].
+	pub alpha_threshold: Option<u8>,
+	/// `true` blends translucent pixels into the canvas with [`AlphaImplementations::copy_with_blend`];
+	/// `false` (the default) copies pixels above the threshold as-is, like the avatar overlay does.
+	pub blend: Option<bool>,
+}
+
+/// the JSON body of a `/compose` request: a canvas size and the ordered layers to composite onto it.
+#[derive(Deserialize, Debug)]
+pub struct ComposeRequest {
+	pub width: u32,
+	pub height: u32,
+	pub layers: Vec<ComposeLayer>,
+	pub format: Option<OutputFormat>,
+	pub quality: Option<u8>,
+	/// a `#RRGGBB`/`RRGGBB` hex or `r,g,b` color (see [`parse_color`]) the canvas starts filled
+	/// with, and that it's flattened onto if encoded as JPEG.
+	pub background: Option<String>,
+}
+
+/// composites `request.layers` onto a blank canvas of `request.width` x `request.height`, in
+/// order, and encodes the result as the requested [`OutputFormat`]. this is the data-driven
+/// counterpart to hardcoded meme functions like [`enter`]/[`exit`]: new memes can be assembled
+/// entirely from layer JSON instead of a new compiled function and `Index` entry.
+pub async fn compose(
+	request: ComposeRequest,
+	max_file_size_bytes: u64,
+) -> Result<HttpResponse, APIResponseError> {
+	let background = match &request.background {
+		Some(value) => parse_color(value)?,
+		None => Rgba([255, 255, 255, 255]),
+	};
+	let format = request.format.unwrap_or_default();
+	let quality = validate_jpeg_quality(request.quality.unwrap_or(DEFAULT_JPEG_QUALITY))?;
+
+	validate_compose_dimensions(request.width, request.height)?;
+	if request.layers.len() > MAX_COMPOSE_LAYERS {
+		return Err(APIResponseError::new(format!(
+			"a compose request may not specify more than {} layers",
+			MAX_COMPOSE_LAYERS
+		)));
+	}
+
+	let mut canvas = RgbaImage::from_pixel(request.width, request.height, background);
+
+	for layer in request.layers {
+		composite_layer(&mut canvas, layer, max_file_size_bytes).await?;
+	}
+
+	let encode_assistant =
+		EncodeAssistant::encode(DynamicImage::ImageRgba8(canvas), format, quality, background)?;
+
+	Ok(HttpResponse::Ok()
+		.content_type(encode_assistant.content_type)
+		.body(encode_assistant.encoding_bytes))
+}
+
+/// loads, transforms, and places a single [`ComposeLayer`] onto `canvas`.
+async fn composite_layer(
+	canvas: &mut RgbaImage,
+	layer: ComposeLayer,
+	max_file_size_bytes: u64,
+) -> Result<(), APIResponseError> {
+	validate_compose_dimensions(layer.width, layer.height)?;
+
+	let image = load_layer_source(&layer, max_file_size_bytes).await?;
+	let mut image = image.resize_exact(layer.width, layer.height, FilterType::Triangle);
+
+	if let Some(degrees) = layer.rotate {
+		image = image.rotate(degrees);
+	}
+	if layer.flip.unwrap_or(false) {
+		image = image.fliph();
+	}
+	if layer.round.unwrap_or(false) {
+		let mut rounded = image.to_rgba8();
+		round_image(&mut rounded);
+		image = DynamicImage::ImageRgba8(rounded);
+	}
+
+	let cropped = out_of_bounds_crop(image, layer.x, layer.y, canvas.width(), canvas.height());
+	let threshold = layer.alpha_threshold.unwrap_or(ALPHA_THRESHOLD);
+
+	if layer.blend.unwrap_or(false) {
+		canvas
+			.copy_with_blend(&cropped.image, cropped.x_pos, cropped.y_pos, threshold)
+			.proper_result()
+	} else {
+		canvas
+			.copy_within_alpha_threshold(&cropped.image, cropped.x_pos, cropped.y_pos, threshold)
+			.proper_result()
+	}
+}
+
+/// resolves a layer's `asset` (from disk) or `url` (downloaded, subject to `max_file_size_bytes`).
+async fn load_layer_source(
+	layer: &ComposeLayer,
+	max_file_size_bytes: u64,
+) -> Result<DynamicImage, APIResponseError> {
+	if let Some(asset) = &layer.asset {
+		return resolve_asset_path(asset).await;
+	}
+
+	if let Some(url) = &layer.url {
+		let bytes = image_request(url, max_file_size_bytes).await?;
+		return image::load_from_memory(&bytes).proper_result();
+	}
+
+	Err(APIResponseError::new(
+		"a layer must specify either 'asset' or 'url'".to_owned(),
+	))
 }
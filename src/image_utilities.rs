@@ -16,10 +16,13 @@
 use image::{
 	codecs::{gif, png::PngEncoder},
 	error::{ParameterError, ParameterErrorKind},
+	imageops::FilterType,
 	io::Reader as ImageReader,
-	Delay, DynamicImage, Frame, GenericImage, GenericImageView, ImageBuffer, ImageEncoder,
-	ImageError, ImageResult, Pixel, Rgba, RgbaImage,
+	AnimationDecoder, Delay, DynamicImage, Frame, GenericImage, GenericImageView, ImageBuffer,
+	ImageEncoder, ImageError, ImageFormat, ImageResult, Pixel, Rgba, RgbaImage,
 };
+use std::io::Cursor;
+use std::path::{Component, Path};
 
 use crate::handler::APIResponseError;
 
@@ -128,27 +131,150 @@ impl AlphaImplementations for ImageBuffer<Rgba<u8>, Vec<u8>> {
 	}
 }
 
-pub async fn image_request(image: &str) -> Result<Vec<u8>, APIResponseError> {
-	let request = match reqwest::get(image).await {
+/// downloads `image`, rejecting non-image responses and enforcing `max_file_size_bytes`
+/// (`0` means unlimited). the `Content-Length` header is checked up front, but since a server
+/// can lie about it, the body is also streamed in chunks and the download is aborted the
+/// moment the cap is crossed.
+pub async fn image_request(
+	image: &str,
+	max_file_size_bytes: u64,
+) -> Result<Vec<u8>, APIResponseError> {
+	let mut response = match reqwest::get(image).await {
 		Ok(value) => value,
 		Err(error) => return Err(APIResponseError::new(error.to_string())),
 	};
 
-	match request.bytes().await {
-		Ok(value) => Ok(value.to_vec()),
-		Err(error) => Err(APIResponseError::new(error.to_string())),
+	let content_type = response
+		.headers()
+		.get(reqwest::header::CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+		.unwrap_or("");
+	if !content_type.starts_with("image/") {
+		return Err(APIResponseError::new(format!(
+			"expected an image response, got content-type '{}'",
+			content_type
+		)));
+	}
+
+	if max_file_size_bytes > 0 {
+		if let Some(content_length) = response.content_length() {
+			if content_length > max_file_size_bytes {
+				return Err(APIResponseError::new(format!(
+					"image exceeds the maximum allowed size of {} bytes",
+					max_file_size_bytes
+				)));
+			}
+		}
+	}
+
+	let mut bytes = Vec::new();
+	while let Some(chunk) = match response.chunk().await {
+		Ok(value) => value,
+		Err(error) => return Err(APIResponseError::new(error.to_string())),
+	} {
+		bytes.extend_from_slice(&chunk);
+
+		if max_file_size_bytes > 0 && bytes.len() as u64 > max_file_size_bytes {
+			return Err(APIResponseError::new(format!(
+				"image exceeds the maximum allowed size of {} bytes",
+				max_file_size_bytes
+			)));
+		}
 	}
+
+	Ok(bytes)
 }
 
-pub async fn resolve_asset_path(
-	image: &str,
-	large: bool,
-) -> Result<DynamicImage, APIResponseError> {
-	let image_readout = match ImageReader::open(format!(
-		"assets/images/{}/{}",
-		if large { 1000 } else { 250 },
-		image
-	)) {
+/// the resolution that every asset on disk is authored at, and that avatar/asset coordinates
+/// in the meme functions are expressed as fractions of.
+pub const BASE_SIZE: u32 = 1000;
+
+/// the `size` used when the query param is omitted, matching the old `large=false` default (the
+/// `250/` asset tree) so existing callers that never opted into `large=true` see no change.
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 250;
+
+/// the largest `size` a caller may request. `resize`/`resize_exact` will happily try to
+/// allocate whatever dimensions they're given, so an unbounded `size` is an unauthenticated
+/// way to make the process allocate an enormous buffer; this keeps worst-case allocation sane.
+pub const MAX_THUMBNAIL_SIZE: u32 = 2048;
+
+/// rejects a requested thumbnail `size` that's zero or above [`MAX_THUMBNAIL_SIZE`].
+pub fn validate_thumbnail_size(size: u32) -> Result<u32, APIResponseError> {
+	if size == 0 || size > MAX_THUMBNAIL_SIZE {
+		return Err(APIResponseError::new(format!(
+			"'size' must be between 1 and {}",
+			MAX_THUMBNAIL_SIZE
+		)));
+	}
+
+	Ok(size)
+}
+
+/// how a source image should be fit into a target box that doesn't match its aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailMethod {
+	/// resize to fit within the box, preserving aspect ratio (one dimension may end up smaller).
+	Scale,
+	/// resize to fill the box, preserving aspect ratio, then trim the centered overflow.
+	Crop,
+}
+
+impl Default for ThumbnailMethod {
+	fn default() -> Self {
+		ThumbnailMethod::Scale
+	}
+}
+
+/// resizes `image` into a `target_width` x `target_height` thumbnail using the given method.
+pub fn thumbnail(
+	image: DynamicImage,
+	target_width: u32,
+	target_height: u32,
+	method: ThumbnailMethod,
+) -> DynamicImage {
+	let (src_width, src_height) = (image.width() as f64, image.height() as f64);
+	let (target_w, target_h) = (target_width as f64, target_height as f64);
+
+	match method {
+		ThumbnailMethod::Scale => {
+			image.resize(target_width, target_height, FilterType::Triangle)
+		}
+		ThumbnailMethod::Crop => {
+			let factor = (target_w / src_width).max(target_h / src_height);
+			let resized_width = (src_width * factor).round() as u32;
+			let resized_height = (src_height * factor).round() as u32;
+
+			let resized = image.resize_exact(resized_width, resized_height, FilterType::Triangle);
+
+			let crop_x = (resized_width.saturating_sub(target_width)) / 2;
+			let crop_y = (resized_height.saturating_sub(target_height)) / 2;
+
+			resized.crop_imm(crop_x, crop_y, target_width, target_height)
+		}
+	}
+}
+
+/// scales a coordinate expressed relative to [`BASE_SIZE`] down (or up) to the given target size.
+pub fn scale_to_size(value: u32, size: u32) -> u32 {
+	((value as u64 * size as u64) / BASE_SIZE as u64) as u32
+}
+
+/// `image` may come straight from attacker-controlled JSON (`/compose` layers), so reject any
+/// path that isn't made entirely of plain path segments before it ever reaches the filesystem -
+/// no `..`, no absolute paths, no drive prefixes.
+pub async fn resolve_asset_path(image: &str) -> Result<DynamicImage, APIResponseError> {
+	let is_plain_path = Path::new(image)
+		.components()
+		.all(|component| matches!(component, Component::Normal(_)));
+	if !is_plain_path {
+		return Err(APIResponseError::new(format!(
+			"'{}' is not a valid asset path",
+			image
+		)));
+	}
+
+	let image_readout = match ImageReader::open(Path::new("assets/images").join(image)) {
 		Ok(value) => value,
 		Err(error) => return Err(APIResponseError::new(error.to_string())),
 	};
@@ -193,21 +319,34 @@ pub fn apply_alpha_threshold(img: &mut image::RgbaImage, threshold: u8) {
 	}
 }
 
-/// If the large option is false, divide given number by 4.
-pub fn smallify_large_number(num: u32, large: bool) -> u32 {
-	if large {
-		num
-	} else {
-		num / 4
-	}
-}
-
 pub struct BoundaryCropOutput {
 	pub image: DynamicImage,
 	pub x_pos: u32,
 	pub y_pos: u32,
 }
 
+/// clamps a single axis of an `(pos, size)` placement against `[0, max)`, returning
+/// `(crop_offset, visible_length, placed_pos)`: the offset into the source to crop from, how
+/// much of it remains visible, and where that crop should be placed. works entirely in `i64`
+/// so an arbitrarily negative or out-of-range `pos` never overflows or underflows `try_into`.
+fn clamp_axis(pos: i32, size: u32, max: u32) -> (u32, u32, u32) {
+	let pos = pos as i64;
+	let size = size as i64;
+	let max = max as i64;
+
+	let visible_start = pos.max(0);
+	let visible_end = (pos + size).min(max);
+
+	if visible_end <= visible_start {
+		return (0, 0, 0);
+	}
+
+	let crop_offset = visible_start - pos;
+	let visible_length = visible_end - visible_start;
+
+	(crop_offset as u32, visible_length as u32, visible_start as u32)
+}
+
 /// a function that simplifies the process of cropping an image when it goes out of an image's boundaries.
 ///
 /// good to use in cases where you're overlaying an image on another image with varying positions and sizes
@@ -218,71 +357,59 @@ pub fn out_of_bounds_crop(
 	max_width: u32,
 	max_height: u32,
 ) -> BoundaryCropOutput {
-	let mut output = BoundaryCropOutput {
-		image,
-		x_pos: 0,
-		y_pos: 0,
-	};
+	let (x_crop_offset, crop_width, out_x) = clamp_axis(x_pos, image.width(), max_width);
+	let (y_crop_offset, crop_height, out_y) = clamp_axis(y_pos, image.height(), max_height);
 
-	// check the y-axis
-	let avatar_height_signed: i32 = output.image.height().try_into().unwrap();
-	let height_total = avatar_height_signed + y_pos;
-
-	if height_total > max_height.try_into().unwrap() {
-		let y_pos_unsigned: u32 = y_pos.try_into().unwrap();
-		let height_total = output.image.height() + y_pos_unsigned;
-		let new_height = output.image.height() - (height_total - max_height);
-
-		output.image = output.image.crop(0, 0, output.image.width(), new_height);
-		output.y_pos = y_pos.try_into().unwrap();
-	} else if y_pos < 0 {
-		output.image = output.image.crop(
-			0,
-			(-y_pos).try_into().unwrap(),
-			output.image.width(),
-			height_total.try_into().unwrap(),
-		);
-	} else {
-		output.y_pos = y_pos.try_into().unwrap();
-	};
+	let mut image = image;
+	let cropped = image.crop(x_crop_offset, y_crop_offset, crop_width, crop_height);
 
-	// check the x-axis
-	let avatar_width_signed: i32 = output.image.width().try_into().unwrap();
-	let width_total = avatar_width_signed + x_pos;
-
-	if width_total > max_width.try_into().unwrap() {
-		let x_pos_unsigned: u32 = x_pos.try_into().unwrap();
-		let width_total = output.image.width() + x_pos_unsigned;
-		let new_width = output.image.width() - (width_total - max_width);
-
-		output.image = output.image.crop(0, 0, new_width, output.image.height());
-		output.x_pos = x_pos.try_into().unwrap();
-	} else if x_pos < 0 {
-		output.image = output.image.crop(
-			0,
-			(-x_pos).try_into().unwrap(),
-			width_total.try_into().unwrap(),
-			output.image.height(),
-		);
-	} else {
-		output.x_pos = x_pos.try_into().unwrap();
-	};
+	BoundaryCropOutput {
+		image: cropped,
+		x_pos: out_x,
+		y_pos: out_y,
+	}
+}
 
-	output
+/// a downloaded avatar: either a single still image, or the frames of an animated GIF,
+/// each paired with the delay it was authored with.
+pub enum Avatar {
+	Static(DynamicImage),
+	Animated(Vec<(RgbaImage, Delay)>),
 }
 
-/// shortens the process for loading a user's avatar, converting to a DynamicImage, then flipping if necessary
+/// shortens the process for loading a user's avatar, detecting whether it's an animated GIF,
+/// and flipping it (every frame, for an animated avatar) if necessary.
 pub async fn load_avatar_from_url(
 	url: String,
 	flip: bool,
-) -> Result<DynamicImage, APIResponseError> {
-	let avatar =
-		image::load_from_memory_with_format(&image_request(&url).await?, image::ImageFormat::Png)
-			.proper_result()?;
-	if flip {
-		return Ok(avatar.fliph());
+	max_file_size_bytes: u64,
+) -> Result<Avatar, APIResponseError> {
+	let bytes = image_request(&url, max_file_size_bytes).await?;
+
+	if image::guess_format(&bytes).proper_result()? == ImageFormat::Gif {
+		let decoder = gif::GifDecoder::new(Cursor::new(&bytes)).proper_result()?;
+		let frames = decoder.into_frames().collect_frames().proper_result()?;
+
+		if frames.len() > 1 {
+			let frames = frames
+				.into_iter()
+				.map(|frame| {
+					let delay = frame.delay();
+					let buffer = if flip {
+						DynamicImage::ImageRgba8(frame.into_buffer()).fliph().to_rgba8()
+					} else {
+						frame.into_buffer()
+					};
+					(buffer, delay)
+				})
+				.collect();
+
+			return Ok(Avatar::Animated(frames));
+		}
 	}
-	Ok(avatar)
+
+	let avatar = image::load_from_memory(&bytes).proper_result()?;
+	Ok(Avatar::Static(if flip { avatar.fliph() } else { avatar }))
 }
 
 pub struct GifAssistant {
@@ -352,6 +479,181 @@ impl PngAssistant {
 	}
 }
 
+/// the default JPEG quality used when the caller doesn't specify one.
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// the valid range for a JPEG `quality`. only meaningful for `OutputFormat::Jpeg`, but validated
+/// regardless of the requested format so a bogus value is rejected up front - `0` in particular
+/// risks a divide-by-zero in the encoder's quantization-table scaling.
+pub const MIN_JPEG_QUALITY: u8 = 1;
+pub const MAX_JPEG_QUALITY: u8 = 100;
+
+/// rejects a `quality` outside [`MIN_JPEG_QUALITY`]..=[`MAX_JPEG_QUALITY`].
+pub fn validate_jpeg_quality(quality: u8) -> Result<u8, APIResponseError> {
+	if !(MIN_JPEG_QUALITY..=MAX_JPEG_QUALITY).contains(&quality) {
+		return Err(APIResponseError::new(format!(
+			"'quality' must be between {} and {}",
+			MIN_JPEG_QUALITY, MAX_JPEG_QUALITY
+		)));
+	}
+
+	Ok(quality)
+}
+
+/// the encoding requested for a composited meme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+	Png,
+	Jpeg,
+	Webp,
+	Gif,
+}
+
+impl Default for OutputFormat {
+	fn default() -> Self {
+		OutputFormat::Png
+	}
+}
+
+/// parses a color given as a `#RRGGBB`/`RRGGBB` hex string, or as an `r,g,b` triple.
+pub fn parse_color(value: &str) -> Result<Rgba<u8>, APIResponseError> {
+	let invalid =
+		|| APIResponseError::new(format!("'{}' isn't a valid #RRGGBB or r,g,b color", value));
+
+	if value.contains(',') {
+		let channels = value
+			.split(',')
+			.map(|part| part.trim().parse::<u8>())
+			.collect::<Result<Vec<u8>, _>>()
+			.map_err(|_| invalid())?;
+
+		return match channels[..] {
+			[r, g, b] => Ok(Rgba([r, g, b, 255])),
+			_ => Err(invalid()),
+		};
+	}
+
+	let hex = value.strip_prefix('#').unwrap_or(value);
+	// `is_ascii` guarantees every byte is its own char, so the byte-index slices below always
+	// land on char boundaries - a bare `len() != 6` check lets a multi-byte UTF-8 value with the
+	// right byte count through and panics on the slice.
+	if hex.len() != 6 || !hex.is_ascii() {
+		return Err(invalid());
+	}
+
+	let channel = |range: std::ops::Range<usize>| -> Result<u8, APIResponseError> {
+		u8::from_str_radix(&hex[range], 16).map_err(|_| invalid())
+	};
+
+	Ok(Rgba([channel(0..2)?, channel(2..4)?, channel(4..6)?, 255]))
+}
+
+/// the default strength applied when a `tint` color is given without an explicit `tint_strength`.
+pub const DEFAULT_TINT_STRENGTH: f32 = 1.0;
+
+/// linearly blends every pixel of `img` above the alpha `threshold` toward `color` by
+/// `strength` (0.0 = unchanged, 1.0 = fully tinted), preserving the pixel's original alpha.
+/// pixels at or below `threshold` are left untouched so rounded/transparent regions stay clean.
+pub fn apply_tint(img: &mut RgbaImage, color: Rgba<u8>, strength: f32, threshold: u8) {
+	for pixel in img.pixels_mut() {
+		if pixel[3] <= threshold {
+			continue;
+		}
+
+		for channel in 0..3 {
+			let blended = pixel[channel] as f32 * (1.0 - strength) + color[channel] as f32 * strength;
+			pixel[channel] = blended.round().clamp(0.0, 255.0) as u8;
+		}
+	}
+}
+
+/// flattens an RGBA image onto a solid background, since formats like JPEG can't hold alpha.
+fn flatten_onto_background(image: &DynamicImage, background: Rgba<u8>) -> image::RgbImage {
+	let rgba = image.to_rgba8();
+	let mut flattened = image::RgbImage::new(rgba.width(), rgba.height());
+
+	for (x, y, pixel) in rgba.enumerate_pixels() {
+		let mut blended = background;
+		blended.blend(pixel);
+		flattened.put_pixel(x, y, blended.to_rgb());
+	}
+
+	flattened
+}
+
+pub struct EncodeAssistant {
+	pub encoding_bytes: Vec<u8>,
+	pub content_type: &'static str,
+}
+
+impl EncodeAssistant {
+	/// encodes `image` as the requested `format`.
+	///
+	/// `quality` only affects JPEG encoding (1-100, default 85). `background` is the color
+	/// composited under the image before JPEG encoding, since JPEG can't hold alpha.
+	pub fn encode(
+		image: DynamicImage,
+		format: OutputFormat,
+		quality: u8,
+		background: Rgba<u8>,
+	) -> Result<EncodeAssistant, APIResponseError> {
+		match format {
+			OutputFormat::Png => {
+				let png_assistant = PngAssistant::create_png(image)?;
+				Ok(EncodeAssistant {
+					encoding_bytes: png_assistant.encoding_bytes,
+					content_type: "image/png",
+				})
+			}
+			OutputFormat::Jpeg => {
+				let flattened = flatten_onto_background(&image, background);
+				let mut encoding_bytes = Vec::new();
+
+				image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoding_bytes, quality)
+					.write_image(
+						flattened.as_raw(),
+						flattened.width(),
+						flattened.height(),
+						image::ColorType::Rgb8,
+					)
+					.proper_result()?;
+
+				Ok(EncodeAssistant {
+					encoding_bytes,
+					content_type: "image/jpeg",
+				})
+			}
+			OutputFormat::Webp => {
+				let rgba = image.to_rgba8();
+				let mut encoding_bytes = Vec::new();
+
+				image::codecs::webp::WebPEncoder::new_lossless(&mut encoding_bytes)
+					.write_image(rgba.as_raw(), rgba.width(), rgba.height(), image::ColorType::Rgba8)
+					.proper_result()?;
+
+				Ok(EncodeAssistant {
+					encoding_bytes,
+					content_type: "image/webp",
+				})
+			}
+			OutputFormat::Gif => {
+				let rgba = image.to_rgba8();
+				let mut encoding_bytes = Vec::new();
+				{
+					let mut encoder = gif::GifEncoder::new(&mut encoding_bytes);
+					encoder.encode_frame(Frame::new(rgba)).proper_result()?;
+				}
+
+				Ok(EncodeAssistant {
+					encoding_bytes,
+					content_type: "image/gif",
+				})
+			}
+		}
+	}
+}
+
 pub trait CustomRotation {
 	/// Rotate an image by a specified amount of radians counter-clockwise and put the result into the destination [`ImageBuffer`].
 	///
@@ -13,18 +13,23 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+mod cache;
 mod config;
 mod handler;
 mod image_manipulation;
 mod image_utilities;
 
 use actix_cors::Cors;
-use actix_web::{App, HttpServer};
-use handler::create;
+use actix_web::{web, App, HttpServer};
+use cache::ResponseCache;
+use handler::{compose, create};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
 	let config = crate::config::Config::new();
+	let server_addr = config.server_addr.clone();
+	let response_cache = web::Data::new(ResponseCache::new(config.cache_capacity));
+	let config_data = web::Data::new(config);
 
 	HttpServer::new(move || {
 		let cors = Cors::default()
@@ -40,12 +45,17 @@ async fn main() -> std::io::Result<()> {
 			})
 			.allowed_methods(vec!["POST"]);
 
-		App::new().wrap(cors).service(create)
+		App::new()
+			.wrap(cors)
+			.app_data(config_data.clone())
+			.app_data(response_cache.clone())
+			.service(create)
+			.service(compose)
 	})
-	.bind(&config.server_addr)?
+	.bind(&server_addr)?
 	.run()
 	.await?;
-	println!("Server running at http://{}/", config.server_addr);
+	println!("Server running at http://{}/", server_addr);
 
 	Ok(())
 }